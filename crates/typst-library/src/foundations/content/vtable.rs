@@ -38,10 +38,11 @@ use std::fmt::{self, Debug, Formatter};
 use std::ops::Deref;
 use std::ptr::NonNull;
 
-use ecow::EcoString;
+use ecow::{eco_format, EcoString};
+use typst_syntax::Span;
 
 use super::raw::RawContent;
-use crate::diag::SourceResult;
+use crate::diag::{SourceDiagnostic, SourceResult};
 use crate::engine::Engine;
 use crate::foundations::{
     Args, CastInfo, Construct, Content, LazyElementStore, NativeElement, NativeScope,
@@ -131,9 +132,244 @@ pub struct ContentVtable<T: 'static = RawContent> {
     /// so that we can store the vtable in a `const` without Rust complaining
     /// about the presence of interior mutability.
     pub(super) store: fn() -> &'static LazyElementStore,
+
+    /// Set if the element as a whole is deprecated.
+    pub(super) deprecation: Option<Deprecation>,
+}
+
+/// Deprecation metadata attached to an element or one of its fields.
+///
+/// Borrows the stability-index idea from rustc/rustdoc: instead of silently
+/// breaking or removing an element or field, it can be marked deprecated
+/// with a message pointing users to a migration path, while still behaving
+/// normally.
+#[derive(Debug, Clone, Copy)]
+pub struct Deprecation {
+    /// The message shown to the user, e.g. explaining what to use instead.
+    pub(super) message: &'static str,
+    /// The version in which this was deprecated, if known.
+    pub(super) since: Option<&'static str>,
+}
+
+impl Deprecation {
+    /// Creates deprecation metadata with just a message.
+    pub const fn new(message: &'static str) -> Self {
+        Self {
+            message,
+            since: None,
+        }
+    }
+
+    /// Attaches the version in which this was deprecated.
+    pub const fn since(mut self, since: &'static str) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Produces a warning diagnostic for this deprecation at `span`.
+    pub fn warning(&self, span: Span) -> SourceDiagnostic {
+        let diagnostic = SourceDiagnostic::warning(span, self.message);
+        match self.since {
+            Some(since) => diagnostic.with_hint(format!("deprecated since {since}")),
+            None => diagnostic,
+        }
+    }
+}
+
+/// The ABI version of [`ContentVtable`] and [`FieldVtable`].
+///
+/// Bumped whenever the layout of either table changes in a way that breaks
+/// binary compatibility with already compiled external elements (see
+/// [`ContentVtable::from_raw_parts`]). A dynamically loaded shared library
+/// must be compiled against the same version as the host to be registered.
+pub const ABI_VERSION: u32 = 1;
+
+/// The raw, individually provided function pointers that make up a
+/// [`ContentVtable`].
+///
+/// This mirrors the fields of `ContentVtable` one-to-one, but is a plain,
+/// non-generic struct whose function pointers already take `RawContent`, so
+/// that code outside the `#[elem]` macro can assemble an already type-erased
+/// vtable as plain data.
+///
+/// This is **not** a language-agnostic C ABI: most fields embed Typst's own
+/// Rust types (`Content`, `Engine`, `Args`, `Value`, `Styles`, `Scope`,
+/// `SourceResult`, `FieldVtable`, ...), none of which are `#[repr(C)]`
+/// themselves. `#[repr(C)]` here only fixes the *layout of this struct's own
+/// fields*. [`ContentVtable::from_raw_parts`] copies the fields by name and
+/// does not check this layout against `ContentVtable`'s at load time or at
+/// any other point. `#[repr(C)]` does not make the fields it contains callable
+/// from outside Rust, or from a different compiler version. A dynamically
+/// loaded shared library can use this to register an element only if it is
+/// built against the exact same `typst-library` crate version (same
+/// [`ABI_VERSION`] *and* same compiler, since `ContentVtable`'s own layout is
+/// not otherwise guaranteed stable). True cross-language FFI would require
+/// making every embedded type independently opaque or `#[repr(C)]`, which is
+/// out of scope here.
+#[repr(C)]
+pub struct RawContentVtableParts {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub docs: &'static str,
+    pub keywords: &'static [&'static str],
+    pub fields: &'static [FieldVtable],
+    pub field_id: fn(name: &str) -> Option<u8>,
+    pub construct: fn(&mut Engine, &mut Args) -> SourceResult<Content>,
+    pub set: fn(&mut Engine, &mut Args) -> SourceResult<Styles>,
+    pub local_name: Option<fn(Lang, Option<Region>) -> &'static str>,
+    pub scope: fn() -> Scope,
+    pub capability: fn(capability: TypeId) -> Option<NonNull<()>>,
+    pub drop: unsafe fn(&mut RawContent),
+    pub clone: unsafe fn(&RawContent) -> RawContent,
+    pub hash: unsafe fn(&RawContent) -> u128,
+    pub debug: unsafe fn(&RawContent, &mut Formatter) -> fmt::Result,
+    pub eq: Option<unsafe fn(&RawContent, &RawContent) -> bool>,
+    pub repr: Option<unsafe fn(&RawContent) -> EcoString>,
+    pub store: fn() -> &'static LazyElementStore,
+    pub deprecation: Option<Deprecation>,
+}
+
+/// An error returned when an external element's ABI version does not match
+/// the host's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbiMismatch {
+    /// The ABI version the host expects, i.e. [`ABI_VERSION`].
+    pub expected: u32,
+    /// The ABI version the external element was built against.
+    pub found: u32,
+}
+
+impl fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "incompatible element ABI version (expected {}, found {})",
+            self.expected, self.found,
+        )
+    }
+}
+
+/// A process-wide registry of externally (dynamically) loaded element
+/// vtables.
+///
+/// Native elements built into the Typst binary identify themselves by
+/// comparing the address of their `&'static ContentVtable` (see
+/// `RawContent::is`). Externally loaded elements use the same trick: the
+/// registry's key is the address of the `'static` vtable that the loading
+/// shared library pinned in its own static storage, since a cross-crate
+/// stable `TypeId` is not available for types defined outside this binary.
+///
+/// This type only validates and stores a vtable that the caller already has
+/// in hand; actually finding and loading a shared library (locating it on
+/// disk, `dlopen`ing it, looking up its `extern "C"` entry symbol, and
+/// calling it to obtain a `RawContentVtableParts`) is a separate concern,
+/// left to a loader that does not exist yet in this crate.
+pub struct ExternalElementRegistry {
+    entries: Vec<(usize, &'static ContentVtable)>,
+}
+
+impl ExternalElementRegistry {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Validates and registers a vtable assembled via
+    /// [`ContentVtable::from_raw_parts`], returning the stable key other
+    /// code can use to look it up again.
+    ///
+    /// # Safety
+    /// `vtable` must have been produced by `from_raw_parts` from function
+    /// pointers that are all valid for the raw content they will be called
+    /// with, per the safety requirements documented on each field of
+    /// `ContentVtable`.
+    pub unsafe fn register(
+        &mut self,
+        vtable: &'static ContentVtable,
+        abi_version: u32,
+    ) -> Result<usize, AbiMismatch> {
+        if abi_version != ABI_VERSION {
+            return Err(AbiMismatch {
+                expected: ABI_VERSION,
+                found: abi_version,
+            });
+        }
+        let key = vtable as *const ContentVtable as usize;
+        self.entries.push((key, vtable));
+        Ok(key)
+    }
+
+    /// Looks up a previously registered vtable by its key.
+    pub fn get(&self, key: usize) -> Option<&'static ContentVtable> {
+        self.entries
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
+            .copied()
+    }
+}
+
+impl Default for ExternalElementRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ContentVtable {
+    /// Assembles a vtable from raw, individually provided function pointers.
+    ///
+    /// This is the entry point for code that wants to contribute native
+    /// elements built from plain data instead of going through the `#[elem]`
+    /// macro directly. `RawContentVtableParts` mirrors `ContentVtable`
+    /// field-for-field, and this copies the parts across one field at a
+    /// time; there is no run-time check.
+    ///
+    /// The result is not yet trusted by the host; pass it to
+    /// [`ExternalElementRegistry::register`] to validate its ABI version
+    /// and make it discoverable. See the type-level docs on
+    /// [`RawContentVtableParts`] for what this boundary does and does not
+    /// guarantee.
+    ///
+    /// Note that this constructs `ContentVtable` by copying each field of
+    /// `parts` by name, not by transmuting the whole struct, so a field that
+    /// is removed, renamed, or retyped in only one of the two structs is
+    /// already rejected by the compiler before this function runs. Nothing
+    /// here validates binary layout across separately compiled binaries;
+    /// only a matching [`ABI_VERSION`] does that, and it is the caller's
+    /// responsibility to bump it whenever either struct's layout changes in
+    /// a way that breaks compatibility with already compiled external
+    /// elements.
+    ///
+    /// # Safety
+    /// The caller must guarantee that every function pointer in `parts`
+    /// satisfies the same contract as the field it is assigned to (see the
+    /// documentation on the corresponding field of `ContentVtable`).
+    pub const unsafe fn from_raw_parts(parts: RawContentVtableParts) -> Self {
+        ContentVtable {
+            name: parts.name,
+            title: parts.title,
+            docs: parts.docs,
+            keywords: parts.keywords,
+            fields: parts.fields,
+            field_id: parts.field_id,
+            construct: parts.construct,
+            set: parts.set,
+            local_name: parts.local_name,
+            scope: parts.scope,
+            capability: parts.capability,
+            drop: parts.drop,
+            clone: parts.clone,
+            hash: parts.hash,
+            debug: parts.debug,
+            eq: parts.eq,
+            repr: parts.repr,
+            store: parts.store,
+            deprecation: parts.deprecation,
+        }
+    }
+
     /// Creates the vtable for an element.
     pub const fn new<E: NativeElement>(
         name: &'static str,
@@ -163,6 +399,7 @@ impl ContentVtable {
             eq: None,
             repr: None,
             store,
+            deprecation: None,
         }
     }
 
@@ -170,6 +407,56 @@ impl ContentVtable {
     pub fn field(&self, id: u8) -> Option<&'static FieldVtable> {
         self.fields.get(usize::from(id))
     }
+
+    /// Retrieves the vtable of the field with the given ID, refusing
+    /// internal fields. Use this instead of [`Self::field`] for any access
+    /// that originates from user code, e.g. `element.field` or a set rule.
+    pub fn field_for_user(&self, id: u8) -> Option<&'static FieldVtable> {
+        self.field(id).filter(|field| !field.internal)
+    }
+
+    /// If the element is deprecated, emits a warning through the engine's
+    /// diagnostic sink.
+    pub fn warn_if_deprecated(&self, engine: &mut Engine, span: Span) {
+        if let Some(deprecation) = &self.deprecation {
+            engine.sink.warn(deprecation.warning(span));
+        }
+    }
+
+    /// The element's deprecation status, if any. Exposed alongside
+    /// [`Self::name`]/[`Self::title`]/[`Self::docs`] so documentation
+    /// generation and editor autocomplete can mark deprecated elements.
+    pub fn deprecation(&self) -> Option<&Deprecation> {
+        self.deprecation.as_ref()
+    }
+
+    /// Runs the element's constructor, first emitting a deprecation warning
+    /// if the element is deprecated. The `construct` field is `pub(super)`,
+    /// so code outside the `content` module can run it only through this
+    /// method. Code inside the `content` module can still call the field
+    /// directly, and then no deprecation warning is emitted.
+    pub fn run_construct(
+        &self,
+        engine: &mut Engine,
+        args: &mut Args,
+    ) -> SourceResult<Content> {
+        self.warn_if_deprecated(engine, args.span);
+        (self.construct)(engine, args)
+    }
+
+    /// Runs the element's set rule, first emitting a deprecation warning if
+    /// the element is deprecated. See [`Self::run_construct`].
+    ///
+    /// This only covers element-level deprecation. No per-field deprecation
+    /// warning is emitted here: the `set` function receives only the engine
+    /// and `args` and returns [`Styles`], with no content to pass to
+    /// [`FieldHandle::set`]. Per-field warnings come only from
+    /// [`FieldVtable::warn_if_deprecated`], directly or through
+    /// [`FieldHandle::set`].
+    pub fn run_set(&self, engine: &mut Engine, args: &mut Args) -> SourceResult<Styles> {
+        self.warn_if_deprecated(engine, args.span);
+        (self.set)(engine, args)
+    }
 }
 
 impl<E: NativeElement> ContentVtable<Packed<E>> {
@@ -215,6 +502,12 @@ impl<E: NativeElement> ContentVtable<Packed<E>> {
         self
     }
 
+    /// Marks the element as deprecated.
+    pub const fn with_deprecation(mut self, deprecation: Deprecation) -> Self {
+        self.deprecation = Some(deprecation);
+        self
+    }
+
     /// Type-erases the data.
     pub const fn erase(self) -> ContentVtable {
         // Safety:
@@ -236,9 +529,12 @@ impl<E: NativeElement> ContentVtable<Packed<E>> {
 }
 
 impl<T> ContentHandle<T> {
-    /// Provides safe access to operations for the field with the given `id`.
+    /// Provides safe access to operations for the field with the given `id`,
+    /// refusing internal fields. This is the path user-facing per-id access
+    /// (e.g. `element.field(name)` or a set rule) must go through; see
+    /// [`ContentVtable::field_for_user`].
     pub(super) fn field(self, id: u8) -> Option<FieldHandle<T>> {
-        self.fields.get(usize::from(id)).map(|vtable| {
+        self.1.field_for_user(id).map(|vtable| {
             // Safety: Field vtables are of same type as the content vtable.
             unsafe { Handle::new(self.0, vtable) }
         })
@@ -254,6 +550,22 @@ impl<T> ContentHandle<T> {
             unsafe { Handle::new(self.0, vtable) }
         })
     }
+
+    /// Like [`Self::fields`], but skips internal fields. Used by the generic
+    /// `repr` fallback (see [`ContentHandle::repr`]) so that it does not
+    /// leak internal bookkeeping fields.
+    pub(super) fn fields_public(self) -> impl Iterator<Item = FieldHandle<T>>
+    where
+        T: Copy,
+    {
+        self.fields
+            .iter()
+            .filter(|vtable| !vtable.internal)
+            .map(move |vtable| {
+                // Safety: Field vtables are of same type as the content vtable.
+                unsafe { Handle::new(self.0, vtable) }
+            })
+    }
 }
 
 impl ContentHandle<&RawContent> {
@@ -263,10 +575,27 @@ impl ContentHandle<&RawContent> {
         unsafe { (self.1.debug)(self.0, f) }
     }
 
-    /// See [`ContentVtable::repr`].
+    /// See [`ContentVtable::repr`]. When the element provides no custom
+    /// `Repr` impl, falls back to a generic `name(field: value, ..)`
+    /// representation built from the element's non-internal fields (see
+    /// [`ContentHandle::fields_public`]), so internal bookkeeping fields
+    /// never leak into the generic representation.
     pub fn repr(&self) -> Option<EcoString> {
-        // Safety: `Handle` has the invariant that the vtable is matching.
-        unsafe { self.1.repr.map(|f| f(self.0)) }
+        if let Some(repr) = self.1.repr {
+            // Safety: `Handle` has the invariant that the vtable is matching.
+            return Some(unsafe { repr(self.0) });
+        }
+        let mut pieces = EcoString::new();
+        for field in self.fields_public() {
+            let Some(value) = field.get() else { continue };
+            if !pieces.is_empty() {
+                pieces.push_str(", ");
+            }
+            pieces.push_str(field.name);
+            pieces.push_str(": ");
+            pieces.push_str(&value.repr());
+        }
+        Some(eco_format!("{}({})", self.1.name, pieces))
     }
 
     /// See [`ContentVtable::clone`].
@@ -280,6 +609,26 @@ impl ContentHandle<&RawContent> {
         // Safety: `Handle` has the invariant that the vtable is matching.
         unsafe { (self.1.hash)(self.0) }
     }
+
+    /// Visits every set field without rewriting it, recursing into any
+    /// nested content, arrays, and dictionaries. A read-only counterpart to
+    /// [`ContentHandle::fold`].
+    pub fn visit(&self, visitor: &mut dyn FieldVisitor) -> SourceResult<()> {
+        for field in self.1.fields {
+            // Safety: `self.1` is this handle's own vtable, so its fields
+            // are valid to call with `self.0`.
+            unsafe {
+                if !(field.has)(self.0) {
+                    continue;
+                }
+                let Some(value) = (field.get)(self.0) else {
+                    continue;
+                };
+                visit_value(&value, visitor)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ContentHandle<&mut RawContent> {
@@ -290,6 +639,35 @@ impl ContentHandle<&mut RawContent> {
         // - The caller satifies the requirements of `drop`
         unsafe { (self.1.drop)(self.0) }
     }
+
+    /// Rewrites every set field through `folder`, recursing into any nested
+    /// content, arrays, and dictionaries the folder produces.
+    ///
+    /// Unset settable and synthesized fields are skipped (see
+    /// [`FieldVtable::has`]). Writes go through the raw `set` pointer
+    /// rather than [`FieldHandle::set`]: folding is an internal rewriting
+    /// mechanism used by show rules, localization passes, and bulk
+    /// query-and-replace, not a user assigning a value, so it must not
+    /// trigger a field's deprecation warning on its own.
+    pub fn fold(&mut self, folder: &mut dyn FieldFolder) -> SourceResult<()> {
+        for field in self.1.fields {
+            // Safety: `self.1` is this handle's own vtable, so its fields
+            // are valid to call with `self.0`.
+            unsafe {
+                if !(field.has)(self.0) {
+                    continue;
+                }
+                let Some(value) = (field.get)(self.0) else {
+                    continue;
+                };
+                let folded = fold_value(value, folder)?;
+                // Safety: `field` is one of `self.1`'s own fields, so it is
+                // valid to call with `self.0`.
+                (field.set)(self.0, folded)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ContentHandle<(&RawContent, &RawContent)> {
@@ -320,6 +698,13 @@ pub struct FieldVtable<T: 'static = RawContent> {
     pub(super) settable: bool,
     /// Whether the field is synthesized (i.e. initially not present).
     pub(super) synthesized: bool,
+    /// Whether the field is internal, i.e. not part of the script-facing
+    /// surface of the element. Internal fields are still stored and usable
+    /// by native code through `get`/`materialize`, but are hidden from
+    /// `field_id`-based user field access, the generic `repr` fallback, and
+    /// generated documentation. The element's `Debug` impl (see
+    /// [`ContentVtable::debug`]) still prints them.
+    pub(super) internal: bool,
     /// Reflects what types the field's parameter accepts.
     pub(super) input: fn() -> CastInfo,
     /// Produces the default value of the field, if any. This would e.g. be
@@ -343,6 +728,55 @@ pub struct FieldVtable<T: 'static = RawContent> {
     pub(super) materialize: unsafe fn(elem: &mut T, styles: StyleChain),
     /// Compares the field for equality.
     pub(super) eq: unsafe fn(a: &T, b: &T) -> bool,
+    /// Writes a new value into the field, casting it the same way the
+    /// field's constructor argument would be cast (i.e. the cast that
+    /// `input` reflects). Generated per field, just like [`Self::get`] and
+    /// [`Self::has`] are: the cast target type differs per field, so this
+    /// cannot be derived generically from `input` alone.
+    pub(super) set: unsafe fn(elem: &mut T, value: Value) -> SourceResult<()>,
+
+    /// Set if the field itself is deprecated (independent of whether the
+    /// whole element is).
+    pub(super) deprecation: Option<Deprecation>,
+}
+
+impl<T> FieldVtable<T> {
+    /// Marks the field as internal, hiding it from script-facing field
+    /// access, the generic `repr` fallback, and generated documentation.
+    pub const fn internal(mut self) -> Self {
+        self.internal = true;
+        self
+    }
+
+    /// Marks the field as deprecated.
+    pub const fn with_deprecation(mut self, deprecation: Deprecation) -> Self {
+        self.deprecation = Some(deprecation);
+        self
+    }
+
+    /// If the field is deprecated, emits a warning through the engine's
+    /// diagnostic sink. Meant to be called when the field is assigned
+    /// through [`Args`], e.g. in a set rule.
+    pub fn warn_if_deprecated(&self, engine: &mut Engine, span: Span) {
+        if let Some(deprecation) = &self.deprecation {
+            engine.sink.warn(deprecation.warning(span));
+        }
+    }
+
+    /// The field's deprecation status, if any. Exposed alongside
+    /// [`Self::name`]/[`Self::docs`] so documentation generation and editor
+    /// autocomplete can mark deprecated parameters.
+    pub fn deprecation(&self) -> Option<&Deprecation> {
+        self.deprecation.as_ref()
+    }
+
+    /// Whether the field is internal, i.e. hidden from the script-facing
+    /// surface of the element. Exposed alongside [`Self::deprecation`] so
+    /// that documentation generation can skip internal fields the same way
+    /// [`ContentHandle::fields_public`] does internally.
+    pub fn is_internal(&self) -> bool {
+        self.internal
+    }
 }
 
 impl FieldHandle<&RawContent> {
@@ -371,6 +805,22 @@ impl FieldHandle<&mut RawContent> {
         // Safety: `Handle` has the invariant that the vtable is matching.
         unsafe { (self.1.materialize)(self.0, styles) }
     }
+
+    /// See [`FieldVtable::set`]. First emits a deprecation warning if the
+    /// field is deprecated, then writes `value` into the content. The
+    /// element-level `set` function (see [`ContentVtable::run_set`]) does
+    /// not call this. [`ContentHandle::fold`] calls the raw `set` function
+    /// pointer instead, so folding emits no deprecation warning.
+    pub fn set(
+        &mut self,
+        engine: &mut Engine,
+        span: Span,
+        value: Value,
+    ) -> SourceResult<()> {
+        self.1.warn_if_deprecated(engine, span);
+        // Safety: `Handle` has the invariant that the vtable is matching.
+        unsafe { (self.1.set)(self.0, value) }
+    }
 }
 
 impl FieldHandle<(&RawContent, &RawContent)> {
@@ -381,3 +831,264 @@ impl FieldHandle<(&RawContent, &RawContent)> {
         unsafe { (self.1.eq)(a, b) }
     }
 }
+
+/// A generic rewriter for field values, akin to rustc's `ty::fold`.
+///
+/// Implement this to transform a single field value; [`ContentHandle::fold`]
+/// (reachable through [`Content::fold`]) takes care of walking every field of
+/// an element and recursing into any nested content, arrays, or
+/// dictionaries the fold produces.
+pub trait FieldFolder {
+    /// Transforms a single field value. Implementations that only care
+    /// about some values should match on `value` and return it unchanged
+    /// (`Ok(value)`) for everything else.
+    fn fold_value(&mut self, value: Value) -> SourceResult<Value>;
+}
+
+/// A read-only counterpart to [`FieldFolder`] that only observes field
+/// values without being able to rewrite them.
+pub trait FieldVisitor {
+    /// Observes a single field value.
+    fn visit_value(&mut self, value: &Value) -> SourceResult<()>;
+}
+
+/// Runs a single value through `folder`, descending into content, arrays,
+/// and dictionaries produced by the fold.
+fn fold_value(value: Value, folder: &mut dyn FieldFolder) -> SourceResult<Value> {
+    let value = folder.fold_value(value)?;
+    Ok(match value {
+        Value::Content(mut content) => {
+            content.fold(folder)?;
+            Value::Content(content)
+        }
+        Value::Array(array) => {
+            let mut items = Vec::with_capacity(array.len());
+            for v in array.into_iter() {
+                items.push(fold_value(v, folder)?);
+            }
+            Value::Array(items.into_iter().collect())
+        }
+        Value::Dict(dict) => {
+            let mut items = Vec::with_capacity(dict.len());
+            for (k, v) in dict.into_iter() {
+                items.push((k, fold_value(v, folder)?));
+            }
+            Value::Dict(items.into_iter().collect())
+        }
+        other => other,
+    })
+}
+
+/// Runs a single value through `visitor`, descending into content, arrays,
+/// and dictionaries without rewriting anything.
+fn visit_value(value: &Value, visitor: &mut dyn FieldVisitor) -> SourceResult<()> {
+    visitor.visit_value(value)?;
+    match value {
+        Value::Content(content) => content.visit(visitor)?,
+        Value::Array(array) => {
+            for v in array.iter() {
+                visit_value(v, visitor)?;
+            }
+        }
+        Value::Dict(dict) => {
+            for (_, v) in dict.iter() {
+                visit_value(v, visitor)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+impl Content {
+    /// Rewrites every set field of this content through `folder`, recursing
+    /// into nested content, arrays, and dictionaries. See [`FieldFolder`].
+    ///
+    /// Content is reference-counted, so other `Content` values may share the
+    /// same underlying `RawContent`. To avoid mutating data aliased by those
+    /// other values, a fresh, uniquely owned `RawContent` is cloned before
+    /// the first write — but only if there is a write to make; content with
+    /// no set fields returns without cloning.
+    pub(crate) fn fold(&mut self, folder: &mut dyn FieldFolder) -> SourceResult<()> {
+        // Safety: `self.vtable` is derived from `self.raw`'s own vtable.
+        let handle = unsafe { ContentHandle::new(&self.raw, self.vtable) };
+        if !handle.fields().any(|field| field.has()) {
+            return Ok(());
+        }
+        self.raw = handle.clone();
+        // Safety: `self.vtable` is derived from `self.raw`'s own vtable, and
+        // the line above made `self.raw` a fresh, uniquely owned clone.
+        let mut handle = unsafe { ContentHandle::new(&mut self.raw, self.vtable) };
+        handle.fold(folder)
+    }
+
+    /// Visits every set field of this content without rewriting it. See
+    /// [`FieldVisitor`].
+    pub(crate) fn visit(&self, visitor: &mut dyn FieldVisitor) -> SourceResult<()> {
+        // Safety: `self.vtable` is derived from `self.raw`'s own vtable.
+        let handle = unsafe { ContentHandle::new(&self.raw, self.vtable) };
+        handle.visit(visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::foundations::Array;
+
+    /// Builds a set of raw parts for a fictitious element with no real
+    /// behavior, to exercise [`ContentVtable::from_raw_parts`] and
+    /// [`ExternalElementRegistry`] without needing a real native element.
+    /// None of the function pointers are ever called.
+    fn stub_parts(fields: &'static [FieldVtable]) -> RawContentVtableParts {
+        RawContentVtableParts {
+            name: "stub",
+            title: "Stub",
+            docs: "",
+            keywords: &[],
+            fields,
+            field_id: |name| match name {
+                "public" => Some(0),
+                "hidden" => Some(1),
+                _ => None,
+            },
+            construct: |_, _| unreachable!(),
+            set: |_, _| unreachable!(),
+            local_name: None,
+            scope: || unreachable!(),
+            capability: |_| None,
+            drop: |_| {},
+            clone: |_| unreachable!(),
+            hash: |_| unreachable!(),
+            debug: |_, _| Ok(()),
+            eq: None,
+            repr: None,
+            store: || unreachable!(),
+            deprecation: None,
+        }
+    }
+
+    #[test]
+    fn external_element_registry_round_trips_and_rejects_abi_mismatch() {
+        let vtable: &'static ContentVtable =
+            Box::leak(Box::new(unsafe { ContentVtable::from_raw_parts(stub_parts(&[])) }));
+
+        let mut registry = ExternalElementRegistry::new();
+        let key = unsafe { registry.register(vtable, ABI_VERSION) }.unwrap();
+        assert_eq!(registry.get(key).map(|v| v.name), Some("stub"));
+
+        let mismatch = unsafe { registry.register(vtable, ABI_VERSION + 1) };
+        assert_eq!(
+            mismatch,
+            Err(AbiMismatch { expected: ABI_VERSION, found: ABI_VERSION + 1 }),
+        );
+    }
+
+    /// Builds a field vtable with no real behavior, to exercise the
+    /// internal/deprecation flags without needing a real native element.
+    /// None of the function pointers are ever called.
+    fn stub_field(name: &'static str, internal: bool) -> FieldVtable {
+        FieldVtable {
+            name,
+            docs: "",
+            positional: false,
+            variadic: false,
+            required: false,
+            settable: true,
+            synthesized: false,
+            internal,
+            input: || unreachable!(),
+            default: None,
+            has: |_| unreachable!(),
+            get: |_| unreachable!(),
+            get_with_styles: |_, _| unreachable!(),
+            get_from_styles: |_| unreachable!(),
+            materialize: |_, _| unreachable!(),
+            eq: |_, _| unreachable!(),
+            set: |_, _| unreachable!(),
+            deprecation: None,
+        }
+    }
+
+    #[test]
+    fn field_vtable_builders_set_internal_and_deprecation_flags() {
+        let field = stub_field("value", false);
+        assert!(!field.is_internal());
+
+        let field = field.internal();
+        assert!(field.is_internal());
+
+        let field =
+            field.with_deprecation(Deprecation::new("use `other` instead").since("0.13"));
+        let dep = field.deprecation().unwrap();
+        assert_eq!(dep.message, "use `other` instead");
+        assert_eq!(dep.since, Some("0.13"));
+    }
+
+    #[test]
+    fn content_vtable_field_for_user_hides_internal_fields() {
+        let fields: &'static [FieldVtable] = Box::leak(
+            vec![stub_field("public", false), stub_field("hidden", true)]
+                .into_boxed_slice(),
+        );
+        let vtable: &'static ContentVtable = Box::leak(Box::new(unsafe {
+            ContentVtable::from_raw_parts(stub_parts(fields))
+        }));
+
+        assert!(vtable.field(0).is_some());
+        assert!(vtable.field(1).is_some());
+        assert!(vtable.field_for_user(0).is_some());
+        assert!(vtable.field_for_user(1).is_none());
+    }
+
+    #[test]
+    fn deprecation_stores_message_and_since() {
+        let dep = Deprecation::new("renamed to `foo`").since("0.12");
+        assert_eq!(dep.message, "renamed to `foo`");
+        assert_eq!(dep.since, Some("0.12"));
+
+        let dep = Deprecation::new("renamed to `foo`");
+        assert_eq!(dep.since, None);
+    }
+
+    struct DoubleInts;
+
+    impl FieldFolder for DoubleInts {
+        fn fold_value(&mut self, value: Value) -> SourceResult<Value> {
+            Ok(match value {
+                Value::Int(n) => Value::Int(n * 2),
+                other => other,
+            })
+        }
+    }
+
+    #[test]
+    fn fold_value_recurses_into_array_elements() {
+        let array = Array::from_iter([Value::Int(1), Value::Int(2), Value::Int(3)]);
+        let folded = fold_value(Value::Array(array), &mut DoubleInts).unwrap();
+        let Value::Array(array) = folded else { panic!("expected an array") };
+        assert_eq!(
+            array.into_iter().collect::<Vec<_>>(),
+            vec![Value::Int(2), Value::Int(4), Value::Int(6)],
+        );
+    }
+
+    struct CountInts(usize);
+
+    impl FieldVisitor for CountInts {
+        fn visit_value(&mut self, value: &Value) -> SourceResult<()> {
+            if matches!(value, Value::Int(_)) {
+                self.0 += 1;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn visit_value_visits_every_nested_array_element() {
+        let array = Array::from_iter([Value::Int(1), Value::Int(2), Value::Int(3)]);
+        let mut counter = CountInts(0);
+        visit_value(&Value::Array(array), &mut counter).unwrap();
+        assert_eq!(counter.0, 3);
+    }
+}